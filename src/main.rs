@@ -1,9 +1,15 @@
-use std::io::{self, BufRead};
-use std::process::{Command, Stdio};
+use std::io::{self, BufRead, BufReader};
+use std::process::{Child, Command, ExitStatus, Stdio};
+use std::thread::JoinHandle;
+use std::time::Duration;
 
 use anyhow::Result;
 use clap::{Arg, ArgAction, Command as Clap};
 
+/// Exit code used when any command failed but the run otherwise
+/// finished on its own, matching `xargs`.
+const FAILURE_EXIT_CODE: i32 = 123;
+
 fn main() -> Result<()> {
     let matches = Clap::new("pipe-while-read")
         .version(env!("CARGO_PKG_VERSION"))
@@ -15,6 +21,71 @@ fn main() -> Result<()> {
                 .action(ArgAction::SetTrue)
                 .help("Show commands without executing"),
         )
+        .arg(
+            Arg::new("max-procs")
+                .long("max-procs")
+                .short('P')
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1")
+                .help("Run up to N commands concurrently"),
+        )
+        .arg(
+            Arg::new("replace")
+                .long("replace")
+                .short('I')
+                .value_name("TOKEN")
+                .help("Substitute TOKEN within the fixed args with the input line, instead of appending it"),
+        )
+        .arg(
+            Arg::new("null")
+                .long("null")
+                .short('0')
+                .action(ArgAction::SetTrue)
+                .help("Input is NUL-delimited instead of newline-delimited, for use with find -print0"),
+        )
+        .arg(
+            Arg::new("prefix")
+                .long("prefix")
+                .action(ArgAction::SetTrue)
+                .help("Capture each command's merged stdout/stderr and tag every line with the input that produced it"),
+        )
+        .arg(
+            // Long-only and intentionally so: `-n` is already taken by
+            // `--dry-run`, so there's no short alias for this one.
+            Arg::new("max-args")
+                .long("max-args")
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("1")
+                .long_help(
+                    "Batch up to N input lines into each command invocation, like xargs -n. \
+                     No short flag: -n is already --dry-run in this tool.",
+                )
+                .help("Batch up to N input lines into each command invocation, like xargs -n"),
+        )
+        .arg(
+            Arg::new("halt-on-error")
+                .long("halt-on-error")
+                .action(ArgAction::SetTrue)
+                .help("Abort immediately, exiting with the same code, on the first command that fails"),
+        )
+        .arg(
+            Arg::new("retries")
+                .long("retries")
+                .value_name("N")
+                .value_parser(clap::value_parser!(usize))
+                .default_value("0")
+                .help("Re-run a failed command up to N times before giving up on it"),
+        )
+        .arg(
+            Arg::new("retry-delay")
+                .long("retry-delay")
+                .value_name("MS")
+                .value_parser(clap::value_parser!(u64))
+                .default_value("0")
+                .help("Milliseconds to sleep between retry attempts"),
+        )
         .arg(
             Arg::new("command")
                 .required(true)
@@ -30,39 +101,283 @@ fn main() -> Result<()> {
         .collect();
 
     let dry_run = matches.get_flag("dry-run");
+    let max_procs = (*matches.get_one::<usize>("max-procs").unwrap()).max(1);
+    let replace_token = matches.get_one::<String>("replace").cloned();
+    let null_delimited = matches.get_flag("null");
+    let prefix = matches.get_flag("prefix");
+    let max_args = (*matches.get_one::<usize>("max-args").unwrap()).max(1);
+    let halt_on_error = matches.get_flag("halt-on-error");
+    let retries = *matches.get_one::<usize>("retries").unwrap();
+    let retry_delay = Duration::from_millis(*matches.get_one::<u64>("retry-delay").unwrap());
     let exe = parts.remove(0);
     let fixed_args = parts;
 
+    let cfg = RunConfig {
+        exe,
+        dry_run,
+        max_procs,
+        prefix,
+        retry: RetryPolicy {
+            retries,
+            delay: retry_delay,
+            halt_on_error,
+        },
+    };
+
     let stdin = io::stdin();
-    let mut last_status: Option<i32> = None;
+    let mut pool: Vec<Job> = Vec::new();
+    let mut batch: Vec<String> = Vec::new();
+    let mut any_failed = false;
 
-    for line_res in stdin.lock().lines() {
+    for line_res in read_records(stdin.lock(), null_delimited) {
         let line = line_res?;
 
-        if dry_run {
-            if fixed_args.is_empty() {
-                println!("[DRY RUN] {} {}", exe, line);
-            } else {
-                println!("[DRY RUN] {} {} {}", exe, fixed_args.join(" "), line);
+        if max_args > 1 {
+            batch.push(line);
+            if batch.len() < max_args {
+                continue;
             }
+            let (args, label) = drain_batch(&fixed_args, &mut batch, replace_token.as_deref());
+            dispatch(args, &label, &mut pool, &cfg, &mut any_failed)?;
             continue;
         }
 
-        let status = Command::new(&exe)
-            .args(&fixed_args)
-            .arg(&line)
+        let args = build_args(&fixed_args, &line, replace_token.as_deref());
+        dispatch(args, &line, &mut pool, &cfg, &mut any_failed)?;
+    }
+
+    if !batch.is_empty() {
+        let (args, label) = drain_batch(&fixed_args, &mut batch, replace_token.as_deref());
+        dispatch(args, &label, &mut pool, &cfg, &mut any_failed)?;
+    }
+
+    while !pool.is_empty() {
+        reap_oldest(&mut pool, &cfg.retry, &mut any_failed)?;
+    }
+
+    if any_failed {
+        std::process::exit(FAILURE_EXIT_CODE);
+    }
+
+    Ok(())
+}
+
+/// How a failed command should be handled: how many times to retry it,
+/// how long to wait between attempts, and whether a failure that
+/// survives all retries should abort the whole run immediately.
+struct RetryPolicy {
+    retries: usize,
+    delay: Duration,
+    halt_on_error: bool,
+}
+
+/// The run-wide settings that stay the same for every line or batch:
+/// the command to invoke, how to invoke it, and how to handle
+/// failures. Bundled together so passing them down to `dispatch` and
+/// `spawn_job` doesn't require a long, ever-growing argument list.
+struct RunConfig {
+    exe: String,
+    dry_run: bool,
+    max_procs: usize,
+    prefix: bool,
+    retry: RetryPolicy,
+}
+
+/// Print the dry-run line for `args`, or else queue a job for `args`,
+/// reaping the pool's oldest job first if it's already at capacity.
+fn dispatch(args: Vec<String>, label: &str, pool: &mut Vec<Job>, cfg: &RunConfig, any_failed: &mut bool) -> Result<()> {
+    if cfg.dry_run {
+        if args.is_empty() {
+            println!("[DRY RUN] {}", cfg.exe);
+        } else {
+            println!("[DRY RUN] {} {}", cfg.exe, args.join(" "));
+        }
+        return Ok(());
+    }
+
+    if pool.len() >= cfg.max_procs {
+        reap_oldest(pool, &cfg.retry, any_failed)?;
+    }
+
+    pool.push(spawn_job(&cfg.exe, &args, label, cfg.prefix)?);
+    Ok(())
+}
+
+/// Take the buffered batch of input lines, clear it, and build the
+/// batched argument list, along with a label describing the batch for
+/// `--prefix` output. With no replacement token configured, this is
+/// `fixed_args` followed by every line in the batch, like `xargs -n`.
+/// With a token configured, `build_args` is applied per line so each
+/// line substitutes the token into its own copy of `fixed_args`,
+/// rather than the token being silently ignored for batched input.
+fn drain_batch(fixed_args: &[String], batch: &mut Vec<String>, token: Option<&str>) -> (Vec<String>, String) {
+    let label = batch.join(" ");
+    let lines = std::mem::take(batch);
+
+    let args = match token {
+        Some(token) => lines
+            .iter()
+            .flat_map(|line| build_args(fixed_args, line, Some(token)))
+            .collect(),
+        None => {
+            let mut args = fixed_args.to_vec();
+            args.extend(lines);
+            args
+        }
+    };
+
+    (args, label)
+}
+
+/// An in-flight child process, plus everything needed to retry it and
+/// the thread draining its captured output when `--prefix` is in
+/// effect.
+struct Job {
+    exe: String,
+    args: Vec<String>,
+    label: String,
+    prefix: bool,
+    child: Child,
+    output_thread: Option<JoinHandle<io::Result<()>>>,
+}
+
+/// Spawn the command for one input line (or batch). With `prefix` set,
+/// the child's stdout and stderr are merged into an `os_pipe` and
+/// drained on a background thread that tags each line with `label`;
+/// otherwise the child inherits the parent's stdout/stderr directly.
+fn spawn_job(exe: &str, args: &[String], label: &str, prefix: bool) -> Result<Job> {
+    let child = if !prefix {
+        Command::new(exe).args(args).stdin(Stdio::null()).spawn()?
+    } else {
+        let (reader, writer) = os_pipe::pipe()?;
+        let writer_clone = writer.try_clone()?;
+
+        let child = Command::new(exe)
+            .args(args)
             .stdin(Stdio::null())
-            .status()?;
+            .stdout(writer)
+            .stderr(writer_clone)
+            .spawn()?;
+
+        let prefix_label = label.to_string();
+        let output_thread = std::thread::spawn(move || -> io::Result<()> {
+            for line_res in BufReader::new(reader).lines() {
+                println!("{}: {}", prefix_label, line_res?);
+            }
+            Ok(())
+        });
+
+        return Ok(Job {
+            exe: exe.to_string(),
+            args: args.to_vec(),
+            label: label.to_string(),
+            prefix,
+            child,
+            output_thread: Some(output_thread),
+        });
+    };
 
-        last_status = status.code();
+    Ok(Job {
+        exe: exe.to_string(),
+        args: args.to_vec(),
+        label: label.to_string(),
+        prefix,
+        child,
+        output_thread: None,
+    })
+}
+
+/// Wait for a job's child to exit and, if `--prefix` was capturing its
+/// output, join the thread that drained it.
+fn finish_job(mut job: Job) -> Result<ExitStatus> {
+    let status = job.child.wait()?;
+
+    if let Some(output_thread) = job.output_thread.take() {
+        output_thread
+            .join()
+            .map_err(|_| anyhow::anyhow!("output thread panicked"))??;
+    }
+
+    Ok(status)
+}
+
+/// Yield successive records from `stdin`, delimited by NUL bytes when
+/// `null_delimited` is set (for safe interop with `find -print0` and
+/// `grep -z`, where records may contain whitespace or newlines) and by
+/// newlines otherwise.
+fn read_records(mut stdin: impl BufRead, null_delimited: bool) -> impl Iterator<Item = io::Result<String>> {
+    std::iter::from_fn(move || {
+        let mut buf = Vec::new();
+        let delim = if null_delimited { b'\0' } else { b'\n' };
+
+        match stdin.read_until(delim, &mut buf) {
+            Ok(0) => None,
+            Ok(_) => {
+                if buf.last() == Some(&delim) {
+                    buf.pop();
+                }
+                if !null_delimited && buf.last() == Some(&b'\r') {
+                    buf.pop();
+                }
+                Some(String::from_utf8(buf).map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e)))
+            }
+            Err(e) => Some(Err(e)),
+        }
+    })
+}
+
+/// Build the argument list for one input line. With no replacement
+/// token configured, the line is appended as the final argument. With
+/// a token configured, every occurrence of `token` within each
+/// `fixed_args` entry is replaced by the line instead (even when
+/// embedded in a larger arg, like `--file={}.txt`), and the line is
+/// *not* appended -- if the token appears nowhere, the line is simply
+/// dropped.
+fn build_args(fixed_args: &[String], line: &str, token: Option<&str>) -> Vec<String> {
+    let Some(token) = token else {
+        let mut args = fixed_args.to_vec();
+        args.push(line.to_string());
+        return args;
+    };
+
+    fixed_args
+        .iter()
+        .map(|arg| arg.replace(token, line))
+        .collect()
+}
 
-        if !status.success() {
-            eprintln!("command exited with {}", status);
+/// Wait for the oldest in-flight job to finish, retrying it (after
+/// `retry.delay`) up to `retry.retries` times if it fails. A failure
+/// that survives all retries marks the run as failed and, when
+/// `retry.halt_on_error` is set, exits the whole process immediately
+/// with that command's exit code rather than continuing to the next
+/// line.
+fn reap_oldest(pool: &mut Vec<Job>, retry: &RetryPolicy, any_failed: &mut bool) -> Result<()> {
+    let job = pool.remove(0);
+    let exe = job.exe.clone();
+    let args = job.args.clone();
+    let label = job.label.clone();
+    let prefix = job.prefix;
+
+    let mut status = finish_job(job)?;
+    let mut attempt = 0;
+
+    while !status.success() && attempt < retry.retries {
+        attempt += 1;
+        if !retry.delay.is_zero() {
+            std::thread::sleep(retry.delay);
         }
+        let retry_job = spawn_job(&exe, &args, &label, prefix)?;
+        status = finish_job(retry_job)?;
     }
 
-    if let Some(code) = last_status {
-        std::process::exit(code);
+    if !status.success() {
+        eprintln!("command exited with {}", status);
+        *any_failed = true;
+
+        if retry.halt_on_error {
+            std::process::exit(status.code().unwrap_or(1));
+        }
     }
 
     Ok(())
@@ -24,3 +24,96 @@ fn executes_command_per_line() {
         .success()
         .stdout("X:one\nX:two\n");
 }
+
+#[test]
+fn replace_substitutes_token_embedded_in_a_larger_arg() {
+    let mut cmd = assert_cmd::cargo::cargo_bin_cmd!("pipe-while-read");
+
+    cmd.args(["-I", "{}", "printf", "got:{}.txt\\n"])
+        .write_stdin("a\nb\n")
+        .assert()
+        .success()
+        .stdout("got:a.txt\ngot:b.txt\n");
+}
+
+#[test]
+fn replace_with_no_token_present_drops_the_line_instead_of_appending() {
+    let mut cmd = assert_cmd::cargo::cargo_bin_cmd!("pipe-while-read");
+
+    cmd.args(["-I", "{}", "printf", "fixed\\n"])
+        .write_stdin("a\nb\n")
+        .assert()
+        .success()
+        .stdout("fixed\nfixed\n");
+}
+
+#[test]
+fn max_procs_runs_all_commands_concurrently() {
+    let mut cmd = assert_cmd::cargo::cargo_bin_cmd!("pipe-while-read");
+
+    cmd.args(["-P", "3", "printf", "X:%s\\n"])
+        .write_stdin("one\ntwo\nthree\n")
+        .assert()
+        .success()
+        .stdout(
+            predicate::str::contains("X:one")
+                .and(predicate::str::contains("X:two"))
+                .and(predicate::str::contains("X:three")),
+        );
+}
+
+#[test]
+fn null_delimited_input_splits_records_on_nul_bytes() {
+    let mut cmd = assert_cmd::cargo::cargo_bin_cmd!("pipe-while-read");
+
+    cmd.args(["-0", "printf", "X:%s\\n"])
+        .write_stdin(b"foo bar\0baz\0".to_vec())
+        .assert()
+        .success()
+        .stdout("X:foo bar\nX:baz\n");
+}
+
+#[test]
+fn prefix_tags_each_captured_output_line_with_its_input() {
+    let mut cmd = assert_cmd::cargo::cargo_bin_cmd!("pipe-while-read");
+
+    cmd.args(["--prefix", "printf", "X:%s\\n"])
+        .write_stdin("one\ntwo\n")
+        .assert()
+        .success()
+        .stdout("one: X:one\ntwo: X:two\n");
+}
+
+#[test]
+fn max_args_batches_lines_and_flushes_the_partial_batch_at_eof() {
+    let mut cmd = assert_cmd::cargo::cargo_bin_cmd!("pipe-while-read");
+
+    // A full batch of 2 ("a", "b") is flushed as one invocation, then
+    // the trailing partial batch ("c") is flushed on its own at EOF.
+    cmd.args(["--max-args", "2", "printf", "[%s]"])
+        .write_stdin("a\nb\nc\n")
+        .assert()
+        .success()
+        .stdout("[a][b][c]");
+}
+
+#[test]
+fn exits_123_when_any_command_fails_but_the_run_keeps_going() {
+    let mut cmd = assert_cmd::cargo::cargo_bin_cmd!("pipe-while-read");
+
+    // `test ok = <line>` fails for every line but "ok".
+    cmd.args(["test", "ok", "="])
+        .write_stdin("ok\nbad\nok\n")
+        .assert()
+        .code(123);
+}
+
+#[test]
+fn halt_on_error_exits_immediately_with_the_failing_commands_own_code() {
+    let mut cmd = assert_cmd::cargo::cargo_bin_cmd!("pipe-while-read");
+
+    cmd.args(["--halt-on-error", "test", "ok", "="])
+        .write_stdin("bad\nok\n")
+        .assert()
+        .code(1);
+}